@@ -7,6 +7,7 @@ use curiefense::utils::RequestMeta;
 use mlua::prelude::*;
 use std::collections::HashMap;
 
+use curiefense::body::DEFAULT_BODY_PARSING_CONFIG;
 use curiefense::content_filter_check_generic_request_map;
 use curiefense::inspect_generic_request_map;
 use curiefense::interface::{Decision, Grasshopper};
@@ -73,7 +74,7 @@ fn inspect_content_filter(
     logs.debug("Inspection init");
     let rmeta: RequestMeta = RequestMeta::from_map(meta)?;
 
-    let reqinfo = map_request(&mut logs, ip, headers, rmeta, mbody)?;
+    let reqinfo = map_request(&mut logs, &DEFAULT_BODY_PARSING_CONFIG, ip, headers, rmeta, mbody)?;
 
     let dec = content_filter_check_generic_request_map(configpath, &reqinfo, &content_filter_id, &mut logs);
     Ok(InspectionResult {
@@ -215,7 +216,7 @@ fn inspect_request<GH: Grasshopper>(
     logs.debug("Inspection init");
     let rmeta: RequestMeta = RequestMeta::from_map(meta)?;
 
-    let reqinfo = map_request(&mut logs, ip, headers, rmeta, mbody)?;
+    let reqinfo = map_request(&mut logs, &DEFAULT_BODY_PARSING_CONFIG, ip, headers, rmeta, mbody)?;
 
     let (dec, tags, masked_rinfo) =
         inspect_generic_request_map(configpath, grasshopper, reqinfo, Tags::default(), &mut logs);
@@ -1,4 +1,4 @@
-use curiefense::body::parse_body;
+use curiefense::body::{parse_body, BodyParsingConfig};
 use curiefense::logs::Logs;
 use curiefense::requestfields::RequestField;
 
@@ -8,9 +8,11 @@ use std::collections::HashMap;
 fn body_test(mcontent_type: Option<&str>, body: &[u8], expected_size: Option<usize>) {
     let mut logs = Logs::default();
     let mut args = RequestField::default();
-    parse_body(&mut logs, &mut args, mcontent_type, body).unwrap();
+    parse_body(&mut logs, &mut args, &BodyParsingConfig::default(), mcontent_type, body).unwrap();
     if let Some(sz) = expected_size {
-        assert_eq!(args.len(), sz);
+        // the decoded base64 companions depend on the values, and are not counted
+        let fields = args.iter().filter(|(k, _)| !k.ends_with("_base64")).count();
+        assert_eq!(fields, sz);
     }
 }
 
@@ -25,10 +27,28 @@ fn json_values(c: &mut Criterion) {
     let val_content = serde_json::to_string(&val).unwrap();
     let val_bytes = val_content.as_bytes();
     c.bench_function("json values", |b| {
-        b.iter(|| body_test(Some("text/json"), black_box(val_bytes), Some(5)))
+        // the fields and the _content_type marker
+        b.iter(|| body_test(Some("text/json"), black_box(val_bytes), Some(6)))
     });
 }
 
+fn json_tiny(c: &mut Criterion) {
+    let mut group = c.benchmark_group("JSON tiny");
+    let body = br#"{"a": 1}"#;
+    for (name, config) in [
+        ("default", BodyParsingConfig::default()),
+        ("fast path", BodyParsingConfig::builder().tiny_body_size(64).build()),
+    ] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut logs = Logs::default();
+                let mut args = RequestField::default();
+                parse_body(&mut logs, &mut args, &config, Some("application/json"), black_box(body)).unwrap();
+            })
+        });
+    }
+}
+
 fn create_json_string_map(sz: usize) -> String {
     let mp: HashMap<String, String> = (0..sz)
         .map(|x| {
@@ -44,7 +64,29 @@ fn json_string_map(c: &mut Criterion) {
     for sz in [1, 100, 10000].iter() {
         group.bench_with_input(BenchmarkId::from_parameter(sz), sz, |b, &size| {
             let mp = create_json_string_map(size);
-            b.iter(|| body_test(Some("text/json"), black_box(mp.as_bytes()), Some(*sz)))
+            b.iter(|| body_test(Some("text/json"), black_box(mp.as_bytes()), Some(*sz + 1)))
+        });
+    }
+}
+
+fn json_string_map_count_only(c: &mut Criterion) {
+    let mut group = c.benchmark_group("JSON map, count only");
+    let config = BodyParsingConfig::builder().count_only(true).build();
+    for sz in [1, 100, 10000].iter() {
+        group.bench_with_input(BenchmarkId::from_parameter(sz), sz, |b, &size| {
+            let mp = create_json_string_map(size);
+            b.iter(|| {
+                let mut logs = Logs::default();
+                let stats = parse_body(
+                    &mut logs,
+                    &mut RequestField::default(),
+                    &config,
+                    Some("text/json"),
+                    black_box(mp.as_bytes()),
+                )
+                .unwrap();
+                assert_eq!(stats.field_count, *sz);
+            })
         });
     }
 }
@@ -79,12 +121,19 @@ fn forms_string_map(c: &mut Criterion) {
     for sz in [1, 100, 10000].iter() {
         group.bench_with_input(BenchmarkId::from_parameter(sz), sz, |b, &size| {
             let mp = create_forms_string_map(size);
-            b.iter(|| body_test(None, black_box(mp.as_bytes()), Some(*sz)))
+            // the fields and the _content_type_absent marker
+            b.iter(|| body_test(None, black_box(mp.as_bytes()), Some(*sz + 1)))
         });
     }
 }
 
-criterion_group!(json, json_values, json_string_map);
+criterion_group!(
+    json,
+    json_values,
+    json_tiny,
+    json_string_map,
+    json_string_map_count_only
+);
 criterion_group!(xml, xml_string_map);
 criterion_group!(forms, forms_string_map);
 criterion_main!(forms, json, xml);
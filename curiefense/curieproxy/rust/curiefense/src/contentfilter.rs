@@ -353,7 +353,7 @@ pub fn masking(req: RequestInfo, profile: &ContentFilterProfile) -> RequestInfo
     mask_section(&mut ri.headers, profile.sections.get(SectionIdx::Headers));
     let cookies_masked = mask_section(&mut ri.cookies, profile.sections.get(SectionIdx::Cookies));
     if cookies_masked {
-        ri.headers.0.insert("cookie".into(), "*REDACTED*".into());
+        ri.headers.set("cookie".into(), "*REDACTED*".into());
     }
 
     let arg_masked = mask_section(&mut ri.rinfo.qinfo.args, profile.sections.get(SectionIdx::Args));
@@ -367,6 +367,7 @@ pub fn masking(req: RequestInfo, profile: &ContentFilterProfile) -> RequestInfo
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::body::DEFAULT_BODY_PARSING_CONFIG;
     use crate::utils::{map_request, RequestMeta};
     use crate::Logs;
 
@@ -382,7 +383,15 @@ mod test {
             .iter()
             .map(|(k, v)| (k.to_string(), v.to_string()))
             .collect();
-        map_request(&mut logs, "1.2.3.4".into(), headers, meta, None).unwrap()
+        map_request(
+            &mut logs,
+            &DEFAULT_BODY_PARSING_CONFIG,
+            "1.2.3.4".into(),
+            headers,
+            meta,
+            None,
+        )
+        .unwrap()
     }
 
     #[test]
@@ -414,7 +423,11 @@ mod test {
         assert_eq!(rinfo.headers, masked.headers);
         assert_eq!(rinfo.cookies, masked.cookies);
         assert_eq!(
-            RequestField::raw_create(&[("arg1", "*MASKED*"), ("arg2", "*MASKED*")]),
+            RequestField::raw_create(&[
+                ("arg1", "*MASKED*"),
+                ("arg2", "*MASKED*"),
+                ("__cf_body_absent", "*MASKED*")
+            ]),
             masked.rinfo.qinfo.args
         );
     }
@@ -429,7 +442,7 @@ mod test {
         assert_eq!(rinfo.headers, masked.headers);
         assert_eq!(rinfo.cookies, masked.cookies);
         assert_eq!(
-            RequestField::raw_create(&[("arg1", "*MASKED*"), ("arg2", "avalue2")]),
+            RequestField::raw_create(&[("arg1", "*MASKED*"), ("arg2", "avalue2"), ("__cf_body_absent", "")]),
             masked.rinfo.qinfo.args
         );
     }
@@ -444,7 +457,7 @@ mod test {
         assert_eq!(rinfo.headers, masked.headers);
         assert_eq!(rinfo.cookies, masked.cookies);
         assert_eq!(
-            RequestField::raw_create(&[("arg1", "*MASKED*"), ("arg2", "avalue2")]),
+            RequestField::raw_create(&[("arg1", "*MASKED*"), ("arg2", "avalue2"), ("__cf_body_absent", "")]),
             masked.rinfo.qinfo.args
         );
     }
@@ -459,7 +472,7 @@ mod test {
         assert_eq!(rinfo.headers, masked.headers);
         assert_eq!(rinfo.cookies, masked.cookies);
         assert_eq!(
-            RequestField::raw_create(&[("arg1", "*MASKED*"), ("arg2", "*MASKED*")]),
+            RequestField::raw_create(&[("arg1", "*MASKED*"), ("arg2", "*MASKED*"), ("__cf_body_absent", "")]),
             masked.rinfo.qinfo.args
         );
     }
@@ -1,27 +1,159 @@
 use core::iter::FromIterator;
-use serde::{Deserialize, Serialize};
-use std::collections::{hash_map, HashMap};
+use indexmap::{map, IndexMap};
+use regex::{Regex, RegexSet};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{HashMap, HashSet};
 
-/// a newtype for user supplied data that can collide
+/// a store for user supplied data that can collide
 /// more or less like a HashMap, but concatenates entries with a separator on insert
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
-pub struct RequestField(pub HashMap<String, String>);
+///
+/// fields are kept in insertion order, so that iteration and serialization are reproducible.
+/// The number of collisions is tracked for each key, and the distinct values of colliding keys
+/// are kept, see get_all. Comparison and serialization only take the fields and their merged
+/// values into account.
+#[derive(Debug, Clone, Default)]
+pub struct RequestField {
+    fields: IndexMap<String, String>,
+    /// number of values that were concatenated to the first one, for each key
+    collisions: HashMap<String, usize>,
+    /// when set, a colliding value that is already stored is not concatenated again
+    dedup: bool,
+    /// original type of the values, for the parsers that keep track of it
+    types: HashMap<String, ValueType>,
+    /// separate values of the keys that collided, in insertion order
+    values: HashMap<String, Vec<String>>,
+    /// distinct values of the keys that collided, when deduplicating
+    distinct: HashMap<String, HashSet<String>>,
+    /// source recorded for the fields that are added, when set
+    source: Option<FieldSource>,
+    /// where each field came from, markers are recorded as generated until client data is merged into them
+    sources: HashMap<String, FieldSource>,
+    /// alterations of the values, such as truncation
+    flags: HashMap<String, FieldFlags>,
+}
 
-impl Default for RequestField {
-    fn default() -> Self {
-        RequestField(HashMap::new())
+/// part of the request a field was extracted from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldSource {
+    Query,
+    Body,
+    /// markers added by the parsers
+    Generated,
+}
+
+/// alterations of a value while it was parsed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FieldFlags {
+    /// the value was cut at the maximum length
+    pub truncated: bool,
+    /// invalid parts of the value were replaced, such as invalid UTF-8 sequences
+    pub sanitized: bool,
+}
+
+/// everything that is known about a field, see RequestField::meta
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldMeta {
+    pub source: Option<FieldSource>,
+    pub json_type: Option<ValueType>,
+    pub collision_count: usize,
+    /// length of the (merged) value
+    pub byte_len: usize,
+    pub flags: FieldFlags,
+}
+
+/// type of a value in the document it was parsed from, before it was turned into a string
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    String,
+    Number,
+    Bool,
+    Null,
+    Array,
+    Object,
+}
+
+/// approximate bookkeeping cost of a field: its two strings, and the map entry with its hash
+const FIELD_OVERHEAD: usize = 2 * std::mem::size_of::<String>() + 2 * std::mem::size_of::<usize>();
+
+impl PartialEq for RequestField {
+    fn eq(&self, other: &Self) -> bool {
+        self.fields == other.fields
+    }
+}
+
+impl Eq for RequestField {}
+
+impl Serialize for RequestField {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.fields.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for RequestField {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        IndexMap::deserialize(deserializer).map(|fields| RequestField {
+            fields,
+            ..RequestField::default()
+        })
     }
 }
 
 impl RequestField {
+    /// creates an empty store, preallocated for n fields
+    pub fn with_capacity(n: usize) -> Self {
+        RequestField {
+            fields: IndexMap::with_capacity(n),
+            ..RequestField::default()
+        }
+    }
+
+    /// sets value deduplication: repeated identical values, as in `a=1&a=1`, are only stored once,
+    /// but still counted as collisions
+    pub fn set_dedup(&mut self, dedup: bool) {
+        self.dedup = dedup;
+    }
+
     fn base_add(&mut self, key: String, value: String) {
-        self.0
-            .entry(key)
-            .and_modify(|v| {
-                v.push(' ');
-                v.push_str(&value);
-            })
-            .or_insert(value);
+        match self.fields.entry(key) {
+            map::Entry::Occupied(mut o) => {
+                let key = o.key().clone();
+                let v = o.get_mut();
+                let duplicate = self.dedup
+                    && !self
+                        .distinct
+                        .entry(key.clone())
+                        .or_insert_with(|| std::iter::once(v.clone()).collect())
+                        .insert(value.clone());
+                if !duplicate {
+                    let values = self.values.entry(key.clone()).or_insert_with(|| vec![v.clone()]);
+                    values.push(value.clone());
+                    v.push(' ');
+                    v.push_str(&value);
+                }
+                *self.collisions.entry(key).or_insert(0) += 1;
+            }
+            map::Entry::Vacant(v) => {
+                if let Some(source) = self.source {
+                    self.sources.entry(v.key().clone()).or_insert(source);
+                }
+                v.insert(value);
+            }
+        }
+    }
+
+    /// adds a value extracted from the request
+    ///
+    /// a marker the value collides with is no longer reported as generated, as its value is now
+    /// partly client data
+    fn client_add(&mut self, key: String, value: String) {
+        if self.is_generated(&key) {
+            match self.source {
+                Some(source) => self.sources.insert(key.clone(), source),
+                None => self.sources.remove(&key),
+            };
+        }
+        self.base_add(key, value);
     }
 
     pub fn add(&mut self, key: String, value: String) {
@@ -30,40 +162,358 @@ impl RequestField {
             if let Ok(b64decoded) = base64::decode(value.as_bytes()) {
                 if let Ok(b64value) = String::from_utf8(b64decoded) {
                     let nkey = key.clone() + "_base64";
-                    self.base_add(nkey, b64value);
+                    self.client_add(nkey, b64value);
                 }
             }
         }
+        self.client_add(key, value);
+    }
+
+    /// adds a field generated by the parsers to flag something noteworthy, its value is stored as is
+    pub fn add_marker(&mut self, key: String, value: String) {
+        if !self.fields.contains_key(&key) {
+            self.sources.insert(key.clone(), FieldSource::Generated);
+        }
         self.base_add(key, value);
     }
 
+    /// checks if a field was added by the parsers with add_marker, and not extracted from the request
+    ///
+    /// unlike a check of the marker prefix, a field sent by the client with a name that looks like a
+    /// marker is not reported as generated
+    pub fn is_generated(&self, k: &str) -> bool {
+        self.sources.get(k) == Some(&FieldSource::Generated)
+    }
+
+    /// sets the source recorded for the fields that are added from now on
+    pub fn set_source(&mut self, source: FieldSource) {
+        self.source = Some(source);
+    }
+
+    /// records alterations of the value stored under this key, they are added to the existing ones
+    pub fn set_flags(&mut self, key: &str, flags: FieldFlags) {
+        let current = self.flags.entry(key.to_string()).or_default();
+        current.truncated |= flags.truncated;
+        current.sanitized |= flags.sanitized;
+    }
+
+    /// metadata of a field, None if it is not stored
+    pub fn meta(&self, k: &str) -> Option<FieldMeta> {
+        let value = self.fields.get(k)?;
+        Some(FieldMeta {
+            source: self.sources.get(k).copied(),
+            json_type: self.value_type(k),
+            collision_count: self.collision_count(k),
+            byte_len: value.len(),
+            flags: self.flags.get(k).copied().unwrap_or_default(),
+        })
+    }
+
+    /// folds another store into this one, with the same collision semantics as add
+    pub fn merge(&mut self, other: RequestField) {
+        let RequestField {
+            fields,
+            collisions,
+            types,
+            mut values,
+            sources,
+            flags,
+            ..
+        } = other;
+        for (k, t) in types {
+            self.types.entry(k).or_insert(t);
+        }
+        for (k, src) in sources {
+            self.sources.entry(k).or_insert(src);
+        }
+        for (k, f) in flags {
+            self.set_flags(&k, f);
+        }
+        for (k, v) in fields {
+            let kvalues = values.remove(&k).unwrap_or_else(|| vec![v]);
+            // each added value but the first is counted as a collision, the remaining ones were deduplicated
+            let extra = collisions.get(&k).copied().unwrap_or(0) + 1 - kvalues.len();
+            if extra > 0 {
+                *self.collisions.entry(k.clone()).or_insert(0) += extra;
+            }
+            for value in kvalues {
+                self.base_add(k.clone(), value);
+            }
+        }
+    }
+
+    /// number of values that were concatenated to the first one stored under this key
+    pub fn collision_count(&self, k: &str) -> usize {
+        self.collisions.get(k).copied().unwrap_or(0)
+    }
+
+    /// records the original type of the value stored under this key, the first recorded type is kept
+    pub fn set_value_type(&mut self, key: String, tp: ValueType) {
+        self.types.entry(key).or_insert(tp);
+    }
+
+    /// original type of the value stored under this key, when the parser recorded it
+    pub fn value_type(&self, k: &str) -> Option<ValueType> {
+        self.types.get(k).copied()
+    }
+
     pub fn get(&self, k: &str) -> Option<&String> {
-        self.0.get(k)
+        self.fields.get(k)
     }
 
     pub fn get_str(&self, k: &str) -> Option<&str> {
-        self.0.get(k).map(|s| s.as_str())
+        self.fields.get(k).map(|s| s.as_str())
+    }
+
+    /// all the values that were added under this key, instead of their merged version
+    ///
+    /// deduplicated values are only returned once
+    pub fn get_all(&self, k: &str) -> Vec<&str> {
+        match self.values.get(k) {
+            Some(values) => values.iter().map(|s| s.as_str()).collect(),
+            None => self.get_str(k).into_iter().collect(),
+        }
+    }
+
+    pub fn get_mut(&mut self, k: &str) -> Option<&mut String> {
+        self.fields.get_mut(k)
+    }
+
+    /// stores a value, replacing any existing value for this key instead of concatenating
+    ///
+    /// unlike add, this does not try to decode the value
+    pub fn set(&mut self, key: String, value: String) {
+        self.collisions.remove(&key);
+        self.values.remove(&key);
+        self.distinct.remove(&key);
+        self.flags.remove(&key);
+        self.fields.insert(key, value);
     }
 
     pub fn len(&self) -> usize {
-        self.0.len()
+        self.fields.len()
     }
 
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.fields.is_empty()
+    }
+
+    /// number of fields that can be stored without reallocating
+    pub fn capacity(&self) -> usize {
+        self.fields.capacity()
+    }
+
+    /// rough estimate of the memory used by the fields, to spot requests that produce huge values
+    ///
+    /// this sums the lengths of keys and values, plus a fixed overhead per field, and does not take
+    /// unused capacity into account
+    pub fn approximate_memory_bytes(&self) -> usize {
+        let fields: usize = self
+            .fields
+            .iter()
+            .map(|(k, v)| k.len() + v.len() + FIELD_OVERHEAD)
+            .sum();
+        let values: usize = self
+            .values
+            .values()
+            .flat_map(|vs| vs.iter())
+            .map(|v| v.len() + std::mem::size_of::<String>())
+            .sum();
+        fields + values
+    }
+
+    /// preallocates room for n more fields
+    pub fn reserve(&mut self, n: usize) {
+        self.fields.reserve(n);
+    }
+
+    /// removes all fields, but keeps the allocated memory so that the store can be reused
+    pub fn clear(&mut self) {
+        self.fields.clear();
+        self.collisions.clear();
+        self.types.clear();
+        self.values.clear();
+        self.distinct.clear();
+        self.sources.clear();
+        self.flags.clear();
+    }
+
+    /// iterates over the fields, in insertion order
+    pub fn iter(&self) -> map::Iter<'_, String, String> {
+        self.fields.iter()
+    }
+
+    /// all fields and their (merged) values, in iteration order
+    pub fn to_pairs(&self) -> Vec<(String, String)> {
+        self.fields.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
+
+    /// all fields and their (merged) values, in iteration order
+    pub fn into_pairs(self) -> Vec<(String, String)> {
+        self.fields.into_iter().collect()
     }
 
-    pub fn iter(&self) -> hash_map::Iter<'_, String, String> {
-        self.0.iter()
+    /// a sorted, line oriented representation, with one `key=value` line per field, meant for diffing
+    ///
+    /// backslashes and line breaks are escaped, as well as `=` in keys, so that each line can be split
+    /// unambiguously
+    pub fn to_canonical_string(&self) -> String {
+        let mut lines: Vec<String> = self
+            .fields
+            .iter()
+            .map(|(k, v)| format!("{}={}\n", canonical_escape(k, true), canonical_escape(v, false)))
+            .collect();
+        lines.sort();
+        lines.concat()
     }
 
-    pub fn iter_mut(&mut self) -> hash_map::IterMut<'_, String, String> {
-        self.0.iter_mut()
+    pub fn iter_mut(&mut self) -> map::IterMut<'_, String, String> {
+        self.fields.iter_mut()
     }
 
     #[cfg(test)]
     pub fn raw_create(content: &[(&str, &str)]) -> Self {
-        RequestField(content.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect())
+        RequestField {
+            fields: content.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            ..RequestField::default()
+        }
+    }
+}
+
+fn canonical_escape(s: &str, is_key: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '=' if is_key => out.push_str("\\="),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// key and value patterns of the fields that must be masked in logs
+#[derive(Debug, Clone)]
+pub struct Redaction {
+    /// patterns matched, case insensitively, against field names
+    pub keys: RegexSet,
+    /// patterns matched against field values
+    pub values: RegexSet,
+    /// candidate payment card numbers, values are only masked when the digits of one of them pass the
+    /// Luhn check, so that other long numbers, such as timestamps, are kept
+    pub card_numbers: Option<Regex>,
+}
+
+/// replacement for redacted values
+pub const REDACTED: &str = "***";
+
+impl Redaction {
+    pub fn new<K, V>(keys: K, values: V) -> Result<Self, regex::Error>
+    where
+        K: IntoIterator,
+        K::Item: AsRef<str>,
+        V: IntoIterator,
+        V::Item: AsRef<str>,
+    {
+        Ok(Redaction {
+            keys: RegexSet::new(keys.into_iter().map(|k| format!("(?i){}", k.as_ref())))?,
+            values: RegexSet::new(values)?,
+            card_numbers: None,
+        })
+    }
+
+    pub fn is_redacted(&self, k: &str, v: &str) -> bool {
+        self.keys.is_match(k)
+            || self.values.is_match(v)
+            || self
+                .card_numbers
+                .as_ref()
+                .map(|re| re.find_iter(v).any(|m| luhn_valid(m.as_str())))
+                .unwrap_or(false)
+    }
+}
+
+/// key pattern matching a whole word of the field names, such as `pass` in `user_pass` or `userPass`,
+/// but not in `compass`
+///
+/// words are delimited by characters that are not letters, and by a lowercase letter followed by an
+/// uppercase one
+fn key_word(word: &str) -> String {
+    let (first, rest) = word.split_at(1);
+    format!(
+        "(?:(?:^|[^a-z]){}|(?-i:[a-z]{})){}(?:$|[^a-z]|(?-i:[A-Z]))",
+        first,
+        first.to_ascii_uppercase(),
+        rest
+    )
+}
+
+/// checks the digits of a number with the Luhn algorithm, as done for payment card numbers
+fn luhn_valid(number: &str) -> bool {
+    let sum: u32 = number
+        .bytes()
+        .filter(u8::is_ascii_digit)
+        .rev()
+        .enumerate()
+        .map(|(i, d)| {
+            let d = u32::from(d - b'0');
+            match i % 2 {
+                0 => d,
+                _ if d > 4 => 2 * d - 9,
+                _ => 2 * d,
+            }
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+impl Default for Redaction {
+    fn default() -> Self {
+        let keys = [
+            "pass(word|wd)?",
+            "secret",
+            "token",
+            "authorization",
+            "api[-_]?key",
+            "credit[-_]?card",
+            "card[-_]?number",
+            "cvv",
+        ];
+        let mut redaction = Redaction::new(keys.iter().map(|k| key_word(k)), &[] as &[&str]).unwrap();
+        redaction.card_numbers = Some(Regex::new(r"\b(?:\d[ -]?){12,18}\d\b").unwrap());
+        redaction
+    }
+}
+
+/// a serializable view of a RequestField, where sensitive values are masked
+pub struct Redacted<'a> {
+    fields: &'a RequestField,
+    redaction: &'a Redaction,
+}
+
+impl<'a> Serialize for Redacted<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut mp = serializer.serialize_map(Some(self.fields.len()))?;
+        for (k, v) in self.fields.iter() {
+            if self.redaction.is_redacted(k, v) {
+                mp.serialize_entry(k, REDACTED)?;
+            } else {
+                mp.serialize_entry(k, v)?;
+            }
+        }
+        mp.end()
+    }
+}
+
+impl RequestField {
+    /// view to use when serializing fields to logs, the store itself is left untouched
+    pub fn redacted<'a>(&'a self, redaction: &'a Redaction) -> Redacted<'a> {
+        Redacted {
+            fields: self,
+            redaction,
+        }
     }
 }
 
@@ -76,3 +526,247 @@ impl FromIterator<(String, String)> for RequestField {
         out
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_merges() {
+        let mut rf = RequestField::default();
+        rf.add("a".to_string(), "x".to_string());
+        rf.add("a".to_string(), "y".to_string());
+        assert_eq!(rf.get_str("a"), Some("x y"));
+    }
+
+    #[test]
+    fn set_replaces() {
+        let mut rf = RequestField::default();
+        rf.add("a".to_string(), "x".to_string());
+        rf.set("a".to_string(), "y".to_string());
+        assert_eq!(rf.get_str("a"), Some("y"));
+        rf.set("b".to_string(), "z".to_string());
+        assert_eq!(rf.get_str("b"), Some("z"));
+        assert_eq!(rf.len(), 2);
+    }
+
+    #[test]
+    fn get_mut_modifies() {
+        let mut rf = RequestField::default();
+        rf.add("a".to_string(), "  x  ".to_string());
+        if let Some(v) = rf.get_mut("a") {
+            *v = v.trim().to_string();
+        }
+        assert_eq!(rf.get_str("a"), Some("x"));
+        assert!(rf.get_mut("b").is_none());
+    }
+
+    #[test]
+    fn clear_keeps_capacity() {
+        let mut rf: RequestField = (0..100).map(|i| (format!("k{}", i), "v".to_string())).collect();
+        let capacity = rf.capacity();
+        assert!(capacity >= 100);
+        rf.clear();
+        assert_eq!(rf.len(), 0);
+        assert!(rf.is_empty());
+        assert_eq!(rf.get_str("k1"), None);
+        assert_eq!(rf.capacity(), capacity);
+    }
+
+    #[test]
+    fn with_capacity_like_default() {
+        let mut rf = RequestField::with_capacity(64);
+        let capacity = rf.capacity();
+        assert!(capacity >= 64);
+        assert!(rf.is_empty());
+        let mut rd = RequestField::default();
+        for r in [&mut rf, &mut rd].iter_mut() {
+            r.add("a".to_string(), "x".to_string());
+            r.add("a".to_string(), "y".to_string());
+            r.add("b64".to_string(), "YXJndW1lbnQ=".to_string());
+        }
+        assert_eq!(rf, rd);
+        rf.clear();
+        assert_eq!(rf.capacity(), capacity);
+    }
+
+    #[test]
+    fn collision_counts() {
+        let mut rf = RequestField::default();
+        rf.add("a".to_string(), "x".to_string());
+        assert_eq!(rf.collision_count("a"), 0);
+        rf.add("a".to_string(), "y".to_string());
+        rf.add("a".to_string(), "z".to_string());
+        assert_eq!(rf.collision_count("a"), 2);
+        assert_eq!(rf.get_all("a"), ["x", "y", "z"]);
+        rf.set("a".to_string(), "w".to_string());
+        assert_eq!(rf.collision_count("a"), 0);
+        assert_eq!(rf.get_all("a"), ["w"]);
+        assert!(rf.get_all("missing").is_empty());
+        assert_eq!(rf.collision_count("missing"), 0);
+    }
+
+    #[test]
+    fn merge_stores() {
+        let mut query = RequestField::default();
+        query.add("a".to_string(), "query".to_string());
+        query.add("q".to_string(), "1".to_string());
+        let mut body = RequestField::default();
+        body.add("a".to_string(), "body1".to_string());
+        body.add("a".to_string(), "body2".to_string());
+        body.add("b".to_string(), "2".to_string());
+        query.merge(body);
+        assert_eq!(query.get_str("a"), Some("query body1 body2"));
+        assert_eq!(query.get_all("a"), ["query", "body1", "body2"]);
+        assert_eq!(query.collision_count("a"), 2);
+        assert_eq!(query.get_str("b"), Some("2"));
+        assert_eq!(query.collision_count("b"), 0);
+        assert_eq!(query.get_str("q"), Some("1"));
+        assert_eq!(query.len(), 3);
+    }
+
+    #[test]
+    fn insertion_order() {
+        let mut rf = RequestField::default();
+        for k in &["z", "a", "m", "b"] {
+            rf.add(k.to_string(), "v".to_string());
+        }
+        rf.add("a".to_string(), "w".to_string());
+        rf.set("z".to_string(), "y".to_string());
+        let keys: Vec<&str> = rf.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys, vec!["z", "a", "m", "b"]);
+        assert_eq!(
+            serde_json::to_string(&rf).unwrap(),
+            r#"{"z":"y","a":"v w","m":"v","b":"v"}"#
+        );
+    }
+
+    #[test]
+    fn redaction() {
+        let mut rf = RequestField::default();
+        rf.add("username".to_string(), "bob".to_string());
+        rf.add("Password".to_string(), "hunter2".to_string());
+        rf.add("access_token".to_string(), "abc".to_string());
+        rf.add("comment".to_string(), "card 4111 1111 1111 1111 thanks".to_string());
+        rf.add("order".to_string(), "1234567890123".to_string());
+        rf.add("compass".to_string(), "north".to_string());
+        rf.add("userPassword".to_string(), "x".to_string());
+        rf.add("X-API-KEY".to_string(), "k".to_string());
+        rf.add("tokens".to_string(), "t".to_string());
+        let redaction = Redaction::default();
+        assert_eq!(
+            serde_json::to_value(rf.redacted(&redaction)).unwrap(),
+            serde_json::json!({
+                "username": "bob",
+                "Password": "***",
+                "access_token": "***",
+                "comment": "***",
+                "order": "1234567890123",
+                "compass": "north",
+                "userPassword": "***",
+                "X-API-KEY": "***",
+                "tokens": "t"
+            })
+        );
+        assert_eq!(rf.get_str("Password"), Some("hunter2"));
+
+        let custom = Redaction::new(&["^user"], &[] as &[&str]).unwrap();
+        assert_eq!(
+            serde_json::to_value(rf.redacted(&custom)).unwrap()["username"],
+            serde_json::json!("***")
+        );
+        assert_eq!(
+            serde_json::to_value(rf.redacted(&custom)).unwrap()["Password"],
+            serde_json::json!("hunter2")
+        );
+    }
+
+    #[test]
+    fn dedup_values() {
+        let mut rf = RequestField::default();
+        rf.set_dedup(true);
+        crate::utils::url::parse_urlencoded_params(&mut rf, &Default::default(), "a=1&a=1&a=1&b=x&b=y&b=x&b=z&b=y");
+        assert_eq!(rf.get_str("a"), Some("1"));
+        assert_eq!(rf.collision_count("a"), 2);
+        assert_eq!(rf.get_str("b"), Some("x y z"));
+        assert_eq!(rf.collision_count("b"), 4);
+
+        // values are compared exactly, not with the space separated parts of the merged value
+        let mut rf = RequestField::default();
+        rf.set_dedup(true);
+        crate::utils::url::parse_urlencoded_params(&mut rf, &Default::default(), "a=1%202&a=2&a=1&a=1%202");
+        assert_eq!(rf.get_all("a"), ["1 2", "2", "1"]);
+        assert_eq!(rf.collision_count("a"), 3);
+
+        let mut nodedup = RequestField::default();
+        crate::utils::url::parse_urlencoded_params(&mut nodedup, &Default::default(), "a=1&a=1&a=1");
+        assert_eq!(nodedup.get_str("a"), Some("1 1 1"));
+        assert_eq!(nodedup.collision_count("a"), 2);
+    }
+
+    #[test]
+    fn canonical_escaping() {
+        let mut rf = RequestField::default();
+        rf.add("b".to_string(), "multi\nline\\".to_string());
+        rf.add("a=b".to_string(), "c=d".to_string());
+        assert_eq!(rf.to_canonical_string(), "a\\=b=c=d\nb=multi\\nline\\\\\n");
+    }
+
+    #[test]
+    fn approximate_memory() {
+        let mut rf = RequestField::default();
+        assert_eq!(rf.approximate_memory_bytes(), 0);
+        rf.add("key".to_string(), "x".repeat(1000));
+        let one = rf.approximate_memory_bytes();
+        assert!((1003..1003 + 128).contains(&one), "{}", one);
+        rf.add("k2".to_string(), "y".to_string());
+        assert!(rf.approximate_memory_bytes() > one + 3);
+        rf.clear();
+        assert_eq!(rf.approximate_memory_bytes(), 0);
+    }
+
+    #[test]
+    fn field_meta() {
+        let mut rf = RequestField::default();
+        rf.set_source(FieldSource::Body);
+        let config = crate::body::BodyParsingConfig {
+            max_value_length: Some(3),
+            ..crate::body::BodyParsingConfig::default()
+        };
+        crate::utils::url::parse_urlencoded_params_bytes(&mut rf, &config, b"a=1&a=23456");
+        assert_eq!(
+            rf.meta("a"),
+            Some(FieldMeta {
+                source: Some(FieldSource::Body),
+                json_type: None,
+                collision_count: 1,
+                byte_len: 5,
+                flags: FieldFlags {
+                    truncated: true,
+                    sanitized: false
+                },
+            })
+        );
+        assert_eq!(
+            rf.meta("_value_truncated").map(|m| m.source),
+            Some(Some(FieldSource::Generated))
+        );
+        assert_eq!(rf.meta("missing"), None);
+    }
+
+    #[test]
+    fn generated_fields() {
+        let mut rf = RequestField::default();
+        rf.set_source(FieldSource::Body);
+        rf.add("_body_too_large".to_string(), "client".to_string());
+        rf.add_marker("_body_empty".to_string(), String::new());
+        assert!(!rf.is_generated("_body_too_large"));
+        assert!(rf.is_generated("_body_empty"));
+        assert!(!rf.is_generated("missing"));
+
+        // a client value merged into a marker that was already stored
+        rf.add("_body_empty".to_string(), "spoof".to_string());
+        assert!(!rf.is_generated("_body_empty"));
+        assert_eq!(rf.meta("_body_empty").unwrap().source, Some(FieldSource::Body));
+    }
+}
@@ -130,6 +130,7 @@ pub fn tag_request(is_human: bool, cfg: &Config, rinfo: &RequestInfo) -> (Tags,
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::body::DEFAULT_BODY_PARSING_CONFIG;
     use crate::config::globalfilter::optimize_ipranges;
     use crate::logs::Logs;
     use crate::utils::map_request;
@@ -165,7 +166,15 @@ mod tests {
         }
         let meta = RequestMeta::from_map(attrs).unwrap();
         let mut logs = Logs::default();
-        map_request(&mut logs, "52.78.12.56".to_string(), headers, meta, None).unwrap()
+        map_request(
+            &mut logs,
+            &DEFAULT_BODY_PARSING_CONFIG,
+            "52.78.12.56".to_string(),
+            headers,
+            meta,
+            None,
+        )
+        .unwrap()
     }
 
     fn t_check_entry(negated: bool, entry: GlobalFilterEntryE) -> bool {
@@ -7,12 +7,12 @@ use std::net::IpAddr;
 
 pub mod url;
 
-use crate::body::parse_body;
+use crate::body::{check_content_encoding, parse_body, BodyParsingConfig};
 use crate::config::utils::{RequestSelector, RequestSelectorCondition};
 use crate::interface::{Decision, Tags};
 use crate::logs::Logs;
 use crate::maxmind::{get_asn, get_city};
-use crate::requestfields::RequestField;
+use crate::requestfields::{FieldSource, RequestField};
 use crate::utils::url::parse_urlencoded_params;
 
 pub fn cookie_map(cookies: &mut RequestField, cookie: &str) {
@@ -49,29 +49,41 @@ pub fn map_headers(rawheaders: HashMap<String, String>) -> (RequestField, Reques
 }
 
 /// parses query parameters, such as
-fn parse_query_params(query: &str) -> RequestField {
+fn parse_query_params(config: &BodyParsingConfig, query: &str) -> RequestField {
     let mut rf = RequestField::default();
-    parse_urlencoded_params(&mut rf, query);
+    rf.set_source(FieldSource::Query);
+    parse_urlencoded_params(&mut rf, config, query);
     rf
 }
 
 /// parses the request uri, storing the path and query parts (if possible)
 /// returns the hashmap of arguments
-fn map_args(logs: &mut Logs, path: &str, mcontent_type: Option<&str>, mbody: Option<&[u8]>) -> QueryInfo {
+fn map_args(
+    logs: &mut Logs,
+    config: &BodyParsingConfig,
+    path: &str,
+    mcontent_type: Option<&str>,
+    mbody: Option<&[u8]>,
+) -> QueryInfo {
     // this is necessary to do this in this convoluted way so at not to borrow attrs
     let uri = urlencoding::decode(path).ok();
     let (qpath, query, mut args) = match path.splitn(2, '?').collect_tuple() {
-        Some((qpath, query)) => (qpath.to_string(), query.to_string(), parse_query_params(query)),
+        Some((qpath, query)) => (qpath.to_string(), query.to_string(), parse_query_params(config, query)),
         None => (path.to_string(), String::new(), RequestField::default()),
     };
 
-    if let Some(body) = mbody {
-        if let Err(rr) = parse_body(logs, &mut args, mcontent_type, body) {
-            // if the body could not be parsed, store it in an argument, as if it was text
-            logs.error(rr);
-            args.add("RAW_BODY".to_string(), String::from_utf8_lossy(body).to_string());
-        } else {
-            logs.debug("body parsed");
+    match mbody {
+        // flagged, so that rules can tell it apart from an empty body
+        None => args.add_marker(config.marker_name("_body_absent"), String::new()),
+        Some(body) => {
+            args.set_source(FieldSource::Body);
+            if let Err(rr) = parse_body(logs, &mut args, config, mcontent_type, body) {
+                // if the body could not be parsed, store it in an argument, as if it was text
+                logs.error(rr);
+                args.add("RAW_BODY".to_string(), String::from_utf8_lossy(body).to_string());
+            } else {
+                logs.debug("body parsed");
+            }
         }
     }
 
@@ -340,8 +352,10 @@ pub fn find_geoip(logs: &mut Logs, ipstr: String) -> GeoIp {
     geoip
 }
 
+/// the query string and the body share the same parsing configuration, such as the empty key
 pub fn map_request(
     logs: &mut Logs,
+    config: &BodyParsingConfig,
     ipstr: String,
     headers: HashMap<String, String>,
     meta: RequestMeta,
@@ -352,7 +366,10 @@ pub fn map_request(
     logs.debug("headers mapped");
     let geoip = find_geoip(logs, ipstr);
     logs.debug("geoip computed");
-    let qinfo = map_args(logs, &meta.path, headers.get_str("content-type"), mbody);
+    let mut qinfo = map_args(logs, config, &meta.path, headers.get_str("content-type"), mbody);
+    if let Some(body) = mbody {
+        check_content_encoding(&mut qinfo.args, config, headers.get_str("content-encoding"), body);
+    }
     logs.debug("args mapped");
 
     let host = match meta.authority.as_ref().or_else(|| headers.get("host")) {
@@ -433,6 +450,7 @@ mod tests {
         let mut logs = Logs::default();
         let qinfo = map_args(
             &mut logs,
+            &BodyParsingConfig::default(),
             "/a/b/%20c?xa%20=12&bbbb=12%28&cccc&b64=YXJndW1lbnQ%3D",
             None,
             None,
@@ -445,10 +463,16 @@ mod tests {
         );
         assert_eq!(qinfo.query, "xa%20=12&bbbb=12%28&cccc&b64=YXJndW1lbnQ%3D");
 
-        let expected_args: RequestField = [("xa ", "12"), ("bbbb", "12("), ("cccc", ""), ("b64", "YXJndW1lbnQ=")]
-            .iter()
-            .map(|(k, v)| (k.to_string(), v.to_string()))
-            .collect();
+        let expected_args: RequestField = [
+            ("xa ", "12"),
+            ("bbbb", "12("),
+            ("cccc", ""),
+            ("b64", "YXJndW1lbnQ="),
+            ("__cf_body_absent", ""),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
         assert_eq!(qinfo.args.get("b64_base64").map(|s| s.as_str()), Some("argument"));
         assert_eq!(qinfo.args, expected_args);
     }
@@ -456,12 +480,50 @@ mod tests {
     #[test]
     fn test_map_args_simple() {
         let mut logs = Logs::default();
-        let qinfo = map_args(&mut logs, "/a/b", None, None);
+        let qinfo = map_args(&mut logs, &BodyParsingConfig::default(), "/a/b", None, None);
 
         assert_eq!(qinfo.qpath, "/a/b");
         assert_eq!(qinfo.uri, Some("/a/b".to_string()));
         assert_eq!(qinfo.query, "");
 
-        assert_eq!(qinfo.args, RequestField::default());
+        assert_eq!(qinfo.args, RequestField::raw_create(&[("__cf_body_absent", "")]));
+    }
+
+    #[test]
+    fn test_map_args_bodies() {
+        let mut logs = Logs::default();
+        let absent = map_args(
+            &mut logs,
+            &BodyParsingConfig::default(),
+            "/a/b",
+            Some("application/json"),
+            None,
+        );
+        assert_eq!(absent.args, RequestField::raw_create(&[("__cf_body_absent", "")]));
+        let config = BodyParsingConfig::builder().marker_prefix("cf_").build();
+        let absent = map_args(&mut logs, &config, "/a/b", None, None);
+        assert_eq!(absent.args, RequestField::raw_create(&[("cf_body_absent", "")]));
+        let empty = map_args(
+            &mut logs,
+            &BodyParsingConfig::default(),
+            "/a/b",
+            Some("application/json"),
+            Some(b""),
+        );
+        assert_eq!(
+            empty.args,
+            RequestField::raw_create(&[("__cf_content_type", "application/json"), ("__cf_body_empty", "")])
+        );
+        let parsed = map_args(
+            &mut logs,
+            &BodyParsingConfig::default(),
+            "/a/b",
+            Some("application/json"),
+            Some(b"{}"),
+        );
+        assert_eq!(
+            parsed.args,
+            RequestField::raw_create(&[("__cf_content_type", "application/json")])
+        );
     }
 }
@@ -1,5 +1,10 @@
-use crate::requestfields::RequestField;
+use crate::body::{BodyParsingConfig, BodyVisitor};
+use crate::requestfields::{FieldFlags, RequestField};
 use itertools::Itertools;
+use std::collections::HashMap;
+
+/// key under which parameters with an empty name are stored by default
+pub const EMPTY_KEY: &str = "_empty_key";
 
 #[inline]
 fn from_hex_digit(digit: u8) -> Option<u8> {
@@ -17,9 +22,18 @@ fn urldecode(input: &str) -> Vec<u8> {
 }
 
 fn urldecode_bytes(input: &[u8]) -> Vec<u8> {
+    urldecode_bytes_capped(input, usize::MAX).0
+}
+
+/// decodes at most max bytes, returns true if the input was truncated
+fn urldecode_bytes_capped(input: &[u8], max: usize) -> (Vec<u8>, bool) {
     let mut out = Vec::new();
-    let mut bytes = input.iter().copied();
-    while let Some(mut b) = bytes.next() {
+    let mut bytes = input.iter().copied().peekable();
+    while out.len() < max {
+        let mut b = match bytes.next() {
+            None => break,
+            Some(b) => b,
+        };
         loop {
             if b == b'%' {
                 if let Some(h) = bytes.next() {
@@ -52,7 +66,9 @@ fn urldecode_bytes(input: &[u8]) -> Vec<u8> {
             }
         }
     }
-    out
+    let truncated = out.len() > max || bytes.peek().is_some();
+    out.truncate(max);
+    (out, truncated)
 }
 
 /// decodes an url encoded string into a string, which can contain REPLACEMENT CHARACTER on decoding failure
@@ -60,35 +76,193 @@ pub fn urldecode_str(input: &str) -> String {
     String::from_utf8_lossy(&urldecode(input)).into_owned()
 }
 
+/// converts PHP style bracket keys into the path format used when flattening JSON bodies
+///
+/// `items[0][name]` becomes `items_0_name`, and empty brackets, as in `a[]`, are numbered in order
+/// of appearance, so that `a[]=x&a[]=y` gives the same keys as `{"a": ["x", "y"]}`.
+/// Returns None when the key is not in bracket notation.
+fn bracket_key(key: &str, indices: &mut HashMap<String, usize>) -> Option<String> {
+    let start = key.find('[')?;
+    if start == 0 || !key.ends_with(']') {
+        return None;
+    }
+    let mut out = key[..start].to_string();
+    for segment in key[start + 1..key.len() - 1].split("][") {
+        if segment.contains(['[', ']']) {
+            return None;
+        }
+        out.push('_');
+        if segment.is_empty() {
+            let idx = indices.entry(out.clone()).or_insert(0);
+            out += &format!("{}", idx);
+            *idx += 1;
+        } else {
+            out += segment;
+        }
+    }
+    Some(out)
+}
+
+/// the key under which a decoded parameter is stored
+///
+/// parameters with an empty name are stored under a sentinel key, so that they can't be
+/// used to hide data from rules, and empty segments (as in `a=1&&b=2`) are skipped, their count
+/// being stored in the `_empty_params` marker
+fn param_key(empty_key: &str, k: String, v: &str) -> Option<String> {
+    if !k.is_empty() {
+        Some(k)
+    } else if !v.is_empty() {
+        Some(empty_key.to_string())
+    } else {
+        None
+    }
+}
+
+/// stores a decoded parameter
+fn add_param(args: &mut dyn BodyVisitor, key: String, v: String, flags: FieldFlags) {
+    if flags != FieldFlags::default() {
+        args.flags(&key, flags);
+    }
+    args.field(key, v);
+}
+
 /// parses query parameters, that look like a=b&c=d
-pub fn parse_urlencoded_params(args: &mut RequestField, query: &str) {
+///
+/// the keys are stored as is, bracket keys are only converted in bodies
+pub fn parse_urlencoded_params(args: &mut RequestField, config: &BodyParsingConfig, query: &str) {
+    let mut empty = 0;
     for kv in query.split('&') {
         let (k, v) = match kv.splitn(2, '=').collect_tuple() {
             Some((k, v)) => (urldecode_str(k), urldecode_str(v)),
             None => (urldecode_str(kv), String::new()),
         };
-        args.add(k, v);
+        match param_key(&config.empty_key, k, &v) {
+            Some(key) => add_param(args, key, v, FieldFlags::default()),
+            None => empty += 1,
+        }
+    }
+    if empty > 0 {
+        args.add_marker(config.marker_name("_empty_params"), format!("{}", empty));
+    }
+}
+
+/// converts decoded bytes to a string, returns true if they were not valid UTF-8
+///
+/// an incomplete sequence at the end of truncated input is not reported, as it was cut by the truncation
+fn decoded_to_string(decoded: Vec<u8>, truncated: bool) -> (String, bool) {
+    match String::from_utf8(decoded) {
+        Ok(s) => (s, false),
+        Err(rr) => {
+            let invalid = !truncated || rr.utf8_error().error_len().is_some();
+            (String::from_utf8_lossy(rr.as_bytes()).into_owned(), invalid)
+        }
+    }
+}
+
+/// checks that a body is an url encoded form: it must contain a parameter, and all its keys and
+/// values must decode to valid UTF-8
+pub fn is_urlencoded_form(body: &[u8]) -> bool {
+    body.contains(&b'=')
+        && body.split(|x| *x == b'&').all(|kv| {
+            kv.split(|x| *x == b'=')
+                .all(|part| std::str::from_utf8(&urldecode_bytes(part)).is_ok())
+        })
+}
+
+/// resolves the dot segments of a path, and collapses its duplicate slashes
+///
+/// the segments going above the root of an absolute path are dropped, as servers do
+fn normalize_path_segments(path: &str) -> String {
+    let absolute = path.starts_with('/');
+    let directory = path.ends_with('/') || path.ends_with("/.") || path.ends_with("/..");
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => (),
+            ".." => match segments.last() {
+                Some(last) if *last != ".." => {
+                    segments.pop();
+                }
+                _ if !absolute => segments.push(".."),
+                _ => (),
+            },
+            _ => segments.push(segment),
+        }
+    }
+    let mut out = if absolute { "/".to_string() } else { String::new() };
+    out += &segments.join("/");
+    if directory && !segments.is_empty() {
+        out.push('/');
     }
+    out
 }
 
-fn urldecode_bytes_str(input: &[u8]) -> String {
-    String::from_utf8_lossy(&urldecode_bytes(input)).into_owned()
+/// normalizes the values that look like a path or an URL, returns None for other values
+///
+/// the path of URLs is normalized, their query string and fragment are kept as is
+pub fn normalize_path(value: &str) -> Option<String> {
+    if value.is_empty() || value.contains(char::is_whitespace) {
+        return None;
+    }
+    let (origin, rest) = match value.find("://") {
+        Some(scheme_end) => {
+            let authority = scheme_end + 3;
+            let path_start = value[authority..].find('/')? + authority;
+            value.split_at(path_start)
+        }
+        None if value.starts_with('/') || value.starts_with("./") || value.starts_with("../") => ("", value),
+        None => return None,
+    };
+    let (path, suffix) = rest.split_at(rest.find(['?', '#']).unwrap_or(rest.len()));
+    Some(format!("{}{}{}", origin, normalize_path_segments(path), suffix))
 }
 
 /// parses query parameters, that look like a=b&c=d
-pub fn parse_urlencoded_params_bytes(args: &mut RequestField, query: &[u8]) {
+///
+/// keys and values are decoded the same way, invalid UTF-8 sequences are replaced, and the keys
+/// of such parameters are listed in the `_invalid_utf8` marker.
+/// Values longer than the configured maximum length are truncated, and their keys are listed in
+/// the `_value_truncated` marker. PHP style bracket keys are converted, see bracket_key, and the
+/// markers list the keys the parameters are stored under
+pub fn parse_urlencoded_params_bytes(args: &mut dyn BodyVisitor, config: &BodyParsingConfig, query: &[u8]) {
+    let max_value_length = config.max_value_length.unwrap_or(usize::MAX);
+    let mut indices = HashMap::new();
+    let mut empty = 0;
     for kv in query.split(|x| *x == b'&') {
-        let (k, v) = match kv.splitn(2, |x| *x == b'=').collect_tuple() {
-            Some((k, v)) => (urldecode_bytes_str(k), urldecode_bytes_str(v)),
-            None => (urldecode_bytes_str(kv), String::new()),
+        let (rawk, rawv) = match kv.splitn(2, |x| *x == b'=').collect_tuple() {
+            Some((k, v)) => (k, v),
+            None => (kv, &[] as &[u8]),
+        };
+        let (k, invalid_key) = decoded_to_string(urldecode_bytes(rawk), false);
+        let (decoded, truncated) = urldecode_bytes_capped(rawv, max_value_length);
+        let (v, invalid_value) = decoded_to_string(decoded, truncated);
+        let key = match param_key(&config.empty_key, k, &v) {
+            Some(key) => bracket_key(&key, &mut indices).unwrap_or(key),
+            None => {
+                empty += 1;
+                continue;
+            }
         };
-        args.add(k, v);
+        if truncated {
+            args.marker("_value_truncated".to_string(), key.clone());
+        }
+        if invalid_key || invalid_value {
+            args.marker("_invalid_utf8".to_string(), key.clone());
+        }
+        let flags = FieldFlags {
+            truncated,
+            sanitized: invalid_key || invalid_value,
+        };
+        add_param(args, key, v, flags);
+    }
+    if empty > 0 {
+        args.marker("_empty_params".to_string(), format!("{}", empty));
     }
 }
 
 #[cfg(test)]
 mod test_lib {
-    use super::urldecode_str;
+    use super::*;
 
     #[test]
     fn test_urldecode_normal() {
@@ -119,4 +293,112 @@ mod test_lib {
         assert!(urldecode_str("%F0%9F%91%BE%20Exterminate%21%") == "👾 Exterminate!%");
         assert!(urldecode_str("%F0%9F%BE%20%21%") == "� !%");
     }
+
+    #[test]
+    fn test_empty_key() {
+        let config = BodyParsingConfig::default();
+        let mut args = RequestField::default();
+        parse_urlencoded_params(&mut args, &config, "=secret&a=1&&b&=");
+        assert_eq!(args.get_str(EMPTY_KEY), Some("secret"));
+        assert_eq!(args.get_str("a"), Some("1"));
+        assert_eq!(args.get_str("b"), Some(""));
+        assert_eq!(args.get_str(""), None);
+        assert_eq!(args.get_str("__cf_empty_params"), Some("2"));
+        assert_eq!(args.len(), 4);
+
+        let config = BodyParsingConfig::builder().empty_key("_noname").build();
+        let mut query = RequestField::default();
+        parse_urlencoded_params(&mut query, &config, "=secret&a=1");
+        let mut body = RequestField::default();
+        parse_urlencoded_params_bytes(&mut body, &config, b"=secret&a=1&");
+        assert_eq!(query.get_str("_noname"), Some("secret"));
+        assert_eq!(body.get_str("_noname"), Some("secret"));
+        assert_eq!(query.get_str("__cf_empty_params"), None);
+        assert_eq!(body.get_str("_empty_params"), Some("1"));
+    }
+
+    #[test]
+    fn test_urldecode_capped() {
+        assert_eq!(urldecode_bytes_capped(b"abcd", 4), (b"abcd".to_vec(), false));
+        assert_eq!(urldecode_bytes_capped(b"abcde", 4), (b"abcd".to_vec(), true));
+        assert_eq!(urldecode_bytes_capped(b"%41%42%43", 2), (b"AB".to_vec(), true));
+        assert_eq!(urldecode_bytes_capped(b"%41%42%43", 3), (b"ABC".to_vec(), false));
+        assert_eq!(urldecode_bytes_capped(b"a%%41", 2), (b"a%".to_vec(), true));
+    }
+
+    #[test]
+    fn test_bracket_key() {
+        let mut indices = HashMap::new();
+        assert_eq!(bracket_key("a", &mut indices), None);
+        assert_eq!(bracket_key("[a]", &mut indices), None);
+        assert_eq!(bracket_key("a[b", &mut indices), None);
+        assert_eq!(bracket_key("a[b]c", &mut indices), None);
+        assert_eq!(bracket_key("a[[b]]", &mut indices), None);
+        assert_eq!(bracket_key("a[b]", &mut indices), Some("a_b".to_string()));
+        assert_eq!(bracket_key("a[0][b]", &mut indices), Some("a_0_b".to_string()));
+        assert_eq!(bracket_key("a[]", &mut indices), Some("a_0".to_string()));
+        assert_eq!(bracket_key("a[]", &mut indices), Some("a_1".to_string()));
+        assert_eq!(bracket_key("b[x][]", &mut indices), Some("b_x_0".to_string()));
+    }
+
+    #[test]
+    fn test_utf8_keys() {
+        let mut args = RequestField::default();
+        let config = BodyParsingConfig::default();
+        parse_urlencoded_params_bytes(&mut args, &config, b"%C3%A9=1&caf%C3%A9=%C3%A9t%C3%A9&%FF=%C3%A9");
+        assert_eq!(args.get_str("é"), Some("1"));
+        assert_eq!(args.get_str("café"), Some("été"));
+        assert_eq!(args.get_str("\u{FFFD}"), Some("é"));
+        assert_eq!(args.get_str("_invalid_utf8"), Some("\u{FFFD}"));
+
+        // a sequence cut by truncation is not invalid
+        let mut args = RequestField::default();
+        let config = BodyParsingConfig {
+            max_value_length: Some(1),
+            ..BodyParsingConfig::default()
+        };
+        parse_urlencoded_params_bytes(&mut args, &config, b"a=%C3%A9");
+        assert_eq!(args.get_str("_value_truncated"), Some("a"));
+        assert_eq!(args.get_str("_invalid_utf8"), None);
+
+        let mut args = RequestField::default();
+        parse_urlencoded_params_bytes(&mut args, &config, b"a[b]=xy&c[]=%FF");
+        assert_eq!(args.get_str("_value_truncated"), Some("a_b"));
+        assert_eq!(args.get_str("_invalid_utf8"), Some("c_0"));
+    }
+
+    #[test]
+    fn test_query_bracket_keys() {
+        let mut args = RequestField::default();
+        parse_urlencoded_params(&mut args, &BodyParsingConfig::default(), "ids[]=1&ids[]=2&a[b]=c");
+        assert_eq!(args.get_all("ids[]"), ["1", "2"]);
+        assert_eq!(args.get_str("a[b]"), Some("c"));
+        assert_eq!(args.get_str("ids_0"), None);
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path("/a/../../etc/passwd").as_deref(), Some("/etc/passwd"));
+        assert_eq!(normalize_path("/a//b/./c/").as_deref(), Some("/a/b/c/"));
+        assert_eq!(normalize_path("/a/b/..").as_deref(), Some("/a/"));
+        assert_eq!(normalize_path("../a//b").as_deref(), Some("../a/b"));
+        assert_eq!(normalize_path("./../../a").as_deref(), Some("../../a"));
+        assert_eq!(
+            normalize_path("https://example.com//x/./y/../z?next=/../admin").as_deref(),
+            Some("https://example.com/x/z?next=/../admin")
+        );
+        assert_eq!(normalize_path("https://example.com").as_deref(), None);
+        assert_eq!(normalize_path("not a path").as_deref(), None);
+        assert_eq!(normalize_path("a/b").as_deref(), None);
+    }
+
+    #[test]
+    fn test_is_urlencoded_form() {
+        assert!(is_urlencoded_form(b"a=1&b=2"));
+        assert!(is_urlencoded_form(b"a=hello world&b=line\nbreak"));
+        assert!(is_urlencoded_form("a=été&b=%C3%A9".as_bytes()));
+        assert!(!is_urlencoded_form(b"no parameters"));
+        assert!(!is_urlencoded_form(b"a=%C3&b=2"));
+        assert!(!is_urlencoded_form(b"a=\xff"));
+    }
 }